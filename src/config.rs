@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    IN,
+    OUT
+}
+
+/// Identifies a specific port by its node name and its own `port.name`,
+/// rather than by channel label. Used for `--connect` rules that name a
+/// port explicitly, e.g. `nodeA:FL -> nodeB:FR`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Pair {
+    pub node: String,
+    pub port: String,
+}
+
+impl Pair {
+    pub fn new(node: impl Into<String>, port: impl Into<String>) -> Self {
+        Pair { node: node.into(), port: port.into() }
+    }
+}
+
+/// Whether `s` names a specific port (`node:port`) rather than a whole node.
+pub fn is_pair(s: &str) -> bool {
+    s.contains(':')
+}
+
+/// Splits a `node:port` string into a `Pair`. Panics if `s` isn't one;
+/// callers should check with `is_pair` first.
+pub fn to_pair(s: &str) -> Pair {
+    let (node, port) = s.split_once(':')
+        .unwrap_or_else(|| panic!("to_pair called on a string without ':': {}", s));
+    Pair::new(node, port)
+}
+
+/// The set of routing rules currently in effect, whether they came from the
+/// command line or from a `--config` file. Node names are kept in both
+/// directions in `connect` (and port pairs in both directions in
+/// `port_connect`) so a port belonging to either side of a rule can find its
+/// counterpart in a single lookup.
+#[derive(Debug, Default)]
+pub struct ConfigCache {
+    pub connect: HashMap<String, (String, Direction)>,
+    pub port_connect: HashMap<Pair, (Pair, Direction)>,
+    pub delete_in: HashSet<String>,
+    pub delete_out: HashSet<String>,
+    pub all_names: HashSet<String>
+}
+
+impl ConfigCache {
+    /// Adds a `--connect output input` rule. Each side can either be a plain
+    /// node name (ports matched by `audio.channel`, or by `port.name` for
+    /// ports without a channel, never across media types) or a `node:port` pair
+    /// naming an exact port to allow cross-channel/cross-type routing.
+    pub fn add_connect(&mut self, output: String, input: String) -> Result<(), String> {
+        match (is_pair(&output), is_pair(&input)) {
+            (true, true) => { self.add_port_connect(to_pair(&output), to_pair(&input)); Ok(()) }
+            (false, false) => { self.add_node_connect(output, input); Ok(()) }
+            _ => Err(format!(
+                "--connect rule must name either two nodes (\"a\" \"b\") or two node:port pairs (\"a:out\" \"b:in\"), got \"{}\" and \"{}\"",
+                output, input
+            )),
+        }
+    }
+
+    fn add_node_connect(&mut self, output: String, input: String) {
+        self.all_names.insert(output.clone());
+        self.all_names.insert(input.clone());
+        self.connect.insert(output.clone(), (input.clone(), Direction::IN));
+        self.connect.insert(input, (output, Direction::OUT));
+    }
+
+    /// Drops a node-level connect rule in both directions, e.g. in response
+    /// to a `disconnect` control-socket command. Leaves `all_names` alone:
+    /// the node may still be named by another rule.
+    pub fn remove_connect(&mut self, output: &str, input: &str) {
+        self.connect.remove(output);
+        self.connect.remove(input);
+    }
+
+    pub fn add_port_connect(&mut self, output: Pair, input: Pair) {
+        self.all_names.insert(output.node.clone());
+        self.all_names.insert(input.node.clone());
+        self.port_connect.insert(output.clone(), (input.clone(), Direction::IN));
+        self.port_connect.insert(input, (output, Direction::OUT));
+    }
+
+    /// Drops a `node:port` pair rule in both directions, the `port_connect`
+    /// counterpart of `remove_connect`. Leaves `all_names` alone for the same
+    /// reason `remove_connect` does.
+    pub fn remove_port_connect(&mut self, output: &Pair, input: &Pair) {
+        self.port_connect.remove(output);
+        self.port_connect.remove(input);
+    }
+
+    pub fn add_delete_in(&mut self, node: String) {
+        self.all_names.insert(node.clone());
+        self.delete_in.insert(node);
+    }
+
+    pub fn add_delete_out(&mut self, node: String) {
+        self.all_names.insert(node.clone());
+        self.delete_out.insert(node);
+    }
+
+    /// Folds the rules from a parsed config file into this cache. Bails out
+    /// on the first malformed `connect` rule, leaving `self` unmodified by
+    /// any rule after it so a bad reload can be reported instead of panicking.
+    pub fn merge_file(&mut self, file: FileConfig) -> Result<(), String> {
+        for rule in file.connect {
+            self.add_connect(rule.output, rule.input)?;
+        }
+        for node in file.delete_in {
+            self.add_delete_in(node);
+        }
+        for node in file.delete_out {
+            self.add_delete_out(node);
+        }
+        Ok(())
+    }
+
+    /// Rules present in `new` but not in `self`, keyed by the node name that
+    /// owns the rule (i.e. the name that would show up as `parent.name` in
+    /// `on_new_port`). Covers both whole-node `connect` rules and `node:port`
+    /// pair rules in `port_connect`. Used after a config reload to go back
+    /// and link ports that already exist.
+    pub fn added_connect_rules(&self, new: &ConfigCache) -> Vec<String> {
+        let node_rules = new.connect.keys()
+            .filter(|name| self.connect.get(name.as_str()) != new.connect.get(name.as_str()))
+            .cloned();
+        let port_rules = new.port_connect.keys()
+            .filter(|pair| self.port_connect.get(*pair) != new.port_connect.get(*pair))
+            .map(|pair| pair.node.clone());
+        node_rules.chain(port_rules).collect::<HashSet<_>>().into_iter().collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConnectRule {
+    pub output: String,
+    pub input: String,
+}
+
+/// Shape of a `--config` TOML file:
+///
+/// ```toml
+/// [[connect]]
+/// output = "node_a"
+/// input = "node_b"
+///
+/// [[connect]]
+/// output = "node_a:FL"
+/// input = "node_b:FR"
+///
+/// delete_in = ["node_c"]
+/// delete_out = ["node_d"]
+/// ```
+///
+/// `output`/`input` may name a whole node (ports matched by channel, or by
+/// `port.name` for ports without one) or a
+/// `node:port` pair naming an exact port, same as on the command line.
+#[derive(Debug, Deserialize, Default)]
+pub struct FileConfig {
+    #[serde(default)]
+    pub connect: Vec<ConnectRule>,
+    #[serde(default)]
+    pub delete_in: Vec<String>,
+    #[serde(default)]
+    pub delete_out: Vec<String>,
+}
+
+pub fn load_file(path: &Path) -> Result<FileConfig, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read config file {}: {}", path.display(), e))?;
+    toml::from_str(&text)
+        .map_err(|e| format!("failed to parse config file {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_connect_accepts_two_bare_nodes() {
+        let mut cache = ConfigCache::default();
+        assert!(cache.add_connect("a".to_string(), "b".to_string()).is_ok());
+        assert_eq!(cache.connect.get("a"), Some(&("b".to_string(), Direction::IN)));
+        assert_eq!(cache.connect.get("b"), Some(&("a".to_string(), Direction::OUT)));
+    }
+
+    #[test]
+    fn add_connect_accepts_two_port_pairs() {
+        let mut cache = ConfigCache::default();
+        assert!(cache.add_connect("a:FL".to_string(), "b:FR".to_string()).is_ok());
+        let a_fl = Pair::new("a", "FL");
+        let b_fr = Pair::new("b", "FR");
+        assert_eq!(cache.port_connect.get(&a_fl), Some(&(b_fr.clone(), Direction::IN)));
+        assert_eq!(cache.port_connect.get(&b_fr), Some(&(a_fl, Direction::OUT)));
+    }
+
+    #[test]
+    fn add_connect_rejects_mismatched_pair() {
+        let mut cache = ConfigCache::default();
+        assert!(cache.add_connect("a".to_string(), "b:FR".to_string()).is_err());
+        assert!(cache.connect.is_empty());
+        assert!(cache.port_connect.is_empty());
+    }
+
+    #[test]
+    fn merge_file_stops_at_the_first_bad_rule() {
+        let file = FileConfig {
+            connect: vec![
+                ConnectRule { output: "a".to_string(), input: "b".to_string() },
+                ConnectRule { output: "c".to_string(), input: "d:FR".to_string() },
+                ConnectRule { output: "e".to_string(), input: "f".to_string() },
+            ],
+            delete_in: vec!["g".to_string()],
+            delete_out: vec![],
+        };
+        let mut cache = ConfigCache::default();
+        assert!(cache.merge_file(file).is_err());
+        assert!(cache.connect.contains_key("a"));
+        assert!(!cache.connect.contains_key("e"));
+        // Rules after the `connect` list in the file are never reached.
+        assert!(cache.delete_in.is_empty());
+    }
+
+    #[test]
+    fn added_connect_rules_reports_only_new_rules() {
+        let mut before = ConfigCache::default();
+        before.add_connect("a".to_string(), "b".to_string()).unwrap();
+
+        let mut after = ConfigCache::default();
+        after.add_connect("a".to_string(), "b".to_string()).unwrap();
+        after.add_connect("c".to_string(), "d".to_string()).unwrap();
+
+        let added = before.added_connect_rules(&after);
+        assert_eq!(added.len(), 2);
+        assert!(added.contains(&"c".to_string()));
+        assert!(added.contains(&"d".to_string()));
+    }
+
+    #[test]
+    fn added_connect_rules_reports_new_port_pair_rules() {
+        let before = ConfigCache::default();
+
+        let mut after = ConfigCache::default();
+        after.add_connect("a:FL".to_string(), "b:FR".to_string()).unwrap();
+
+        let added = before.added_connect_rules(&after);
+        assert_eq!(added.len(), 2);
+        assert!(added.contains(&"a".to_string()));
+        assert!(added.contains(&"b".to_string()));
+    }
+
+    #[test]
+    fn remove_connect_drops_both_directions() {
+        let mut cache = ConfigCache::default();
+        cache.add_connect("a".to_string(), "b".to_string()).unwrap();
+        cache.remove_connect("a", "b");
+        assert!(cache.connect.is_empty());
+    }
+
+    #[test]
+    fn remove_port_connect_drops_both_directions() {
+        let mut cache = ConfigCache::default();
+        cache.add_connect("a:FL".to_string(), "b:FR".to_string()).unwrap();
+        cache.remove_port_connect(&Pair::new("a", "FL"), &Pair::new("b", "FR"));
+        assert!(cache.port_connect.is_empty());
+    }
+
+    #[test]
+    fn is_pair_and_to_pair() {
+        assert!(!is_pair("a"));
+        assert!(is_pair("a:FL"));
+        assert_eq!(to_pair("a:FL"), Pair::new("a", "FL"));
+    }
+}
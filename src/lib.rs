@@ -0,0 +1,748 @@
+//! Core link-creation/deletion engine behind the `pipewire-autolink` binary,
+//! exposed so other programs can drive the same logic against their own
+//! PipeWire objects (for example a host backend that needs to auto-wire
+//! nodes it just created) instead of shelling out to the CLI.
+//!
+//! The typical entry point is [`AutoLinker`]: build a [`config::ConfigCache`]
+//! describing the desired rules and call [`AutoLinker::run`]. Callers that
+//! want to drive their own `PipeWire` main loop can instead hold a [`State`]
+//! themselves and call [`on_new_node`]/[`on_new_port`]/[`on_new_link`] from
+//! their own registry listener.
+
+pub mod config;
+
+use std::cell::{RefCell};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::ops::DerefMut;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use pipewire as pw;
+use pw::prelude::*;
+use pw::types::ObjectType;
+use pipewire::proxy::ProxyT;
+use pipewire::registry::{Registry};
+use pipewire::spa::io::IoFlags;
+use pipewire::spa::{ForeignDict, ParsableValue};
+
+use config::{ConfigCache, Direction, Pair};
+
+#[derive(Debug, Clone)]
+pub struct Port {
+    pub id: u32,
+    pub node: u32,
+    // Only present for audio ports; MIDI, video, and generic control ports
+    // have no `audio.channel` and are matched by `name` instead.
+    pub channel: Option<String>,
+    pub name: String,
+    // The type word out of `format.dsp` (e.g. "audio", "midi"), if the port
+    // advertised one. Two ports with a known, differing media type are
+    // never linked together.
+    pub media_type: Option<String>,
+    pub direction: Direction
+}
+
+fn port_label(port: &Port) -> &str {
+    port.channel.as_deref().unwrap_or(port.name.as_str())
+}
+
+// Whether two ports on either side of a whole-node `--connect` rule should
+// be linked: same media type when both are known, and either a matching
+// `audio.channel` or, for channel-less ports, a matching `port.name`.
+fn ports_match(a: &Port, b: &Port) -> bool {
+    if let (Some(a_media), Some(b_media)) = (&a.media_type, &b.media_type) {
+        if a_media != b_media {
+            return false;
+        }
+    }
+    match (&a.channel, &b.channel) {
+        (Some(a_channel), Some(b_channel)) => a_channel == b_channel,
+        _ => a.name == b.name,
+    }
+}
+
+fn media_type_of(format_dsp: &str) -> String {
+    format_dsp.rsplit(' ').next().unwrap_or(format_dsp).to_string()
+}
+
+#[derive(Debug)]
+pub struct NodeData {
+    pub name: String,
+    pub _id: u32,
+    pub ports: Vec<Port>
+}
+
+pub fn create_link(core: &pw::Core, pin: &Port, pout: &Port) -> pw::link::Link {
+    return core.create_object::<pw::link::Link, _>(
+            "link-factory", // TODO: find the link factory the same way the example does
+            &pw::properties! {
+                "link.output.port" => pout.id.to_string(),
+                "link.input.port" => pin.id.to_string(),
+                "link.output.node" => pout.node.to_string(),
+                "link.input.node" => pin.node.to_string(),
+                // Don't remove the object on the remote when we destroy our proxy.
+                "object.linger" => "1"
+            },
+        )
+        .expect("Failed to create object");
+}
+
+#[derive(Default)]
+pub struct State {
+    pub relevant_nodes: HashMap<u32, NodeData>,
+    pub node_by_name: HashMap<String, u32>,
+    pub created_links:  HashSet<u32>, // this should be a vec tbh
+    pub linked_port_pairs: HashSet<(u32, u32)>, // (output port id, input port id)
+    pub links_by_nodes: HashMap<(u32, u32), HashSet<u32>>, // (output node id, input node id) -> link ids
+    pub temp_links: Vec<(pw::link::Link, pw::link::LinkListener)>
+}
+
+pub fn on_delete(id: u32, state: &mut State) {
+    if let Some(data) = state.relevant_nodes.remove(&id) {
+        state.node_by_name.remove(&data.name);
+    }
+    state.created_links.remove(&id);
+}
+
+// Tracks every node PipeWire reports, not just ones named by a rule at the
+// moment it appears: a node that was already running before a `connect`
+// rule naming it was added (e.g. over the control socket) must still be
+// findable by name once that rule shows up, and the `global` event for an
+// already-running node is never replayed. Whether a node actually has a
+// rule is checked later, at link-matching time, in `try_link_port`.
+pub fn on_new_node(name: String, id: u32, state: &mut State) {
+    let name_copy = name.clone();
+    state.relevant_nodes.insert(id, NodeData { name, _id: id, ports: Vec::new() });
+    state.node_by_name.insert(name_copy, id);
+}
+
+// Creates the link for a resolved (input, output) port pair and registers the
+// listener that moves it from `temp_links` into `created_links` once
+// PipeWire confirms it. Shared by the explicit-pair and same-channel paths.
+fn establish_link(
+    state_rc: &Rc<RefCell<State>>,
+    state: &mut State,
+    core: &pw::Core,
+    in_port: &Port,
+    out_port: &Port,
+    in_desc: &str,
+    out_desc: &str,
+) {
+    let pair = (out_port.id, in_port.id);
+    if state.linked_port_pairs.contains(&pair) {
+        return;
+    }
+
+    println!("Creating link from {} to {}", out_desc, in_desc);
+    let link = create_link(core, in_port, out_port);
+    let local_id = link.upcast_ref().id();
+    let node_pair = (out_port.node, in_port.node);
+    let state_copy = state_rc.clone();
+    let listener = link.add_listener_local()
+        .info(move |info| {
+            let mut state = state_copy.borrow_mut();
+            state.created_links.insert(info.id());
+            state.links_by_nodes.entry(node_pair).or_default().insert(info.id());
+            state.temp_links.retain(|(l, _)| l.upcast_ref().id() != local_id);
+        })
+        .register();
+    state.temp_links.push((link, listener));
+    state.linked_port_pairs.insert(pair);
+}
+
+// Tries to link `port` against an explicit `node:port -> node:port` rule
+// naming this exact port.
+fn try_link_explicit_pair(
+    parent_name: &str,
+    port: &Port,
+    state_rc: &Rc<RefCell<State>>,
+    state: &mut State,
+    config: &ConfigCache,
+    core: &pw::Core,
+) {
+    let pair = Pair::new(parent_name, port.name.as_str());
+    let Some((other_pair, other_dir)) = config.port_connect.get(&pair).cloned() else { return };
+    if port.direction == other_dir {
+        return;
+    }
+
+    let Some(&other_node) = state.node_by_name.get(&other_pair.node) else { return };
+    let Some(other_port) = state.relevant_nodes.get(&other_node).unwrap().ports.iter()
+        .find(|p| p.name == other_pair.port && p.direction != port.direction)
+        .cloned() else { return };
+
+    let this_desc = format!("{}:{}", parent_name, port.name);
+    let other_desc = format!("{}:{}", other_pair.node, other_pair.port);
+    if port.direction == Direction::IN {
+        establish_link(state_rc, state, core, port, &other_port, &this_desc, &other_desc);
+    } else {
+        establish_link(state_rc, state, core, &other_port, port, &other_desc, &this_desc);
+    }
+}
+
+// Tries to link `port` against the whole-node `--connect` rule for
+// `parent_name`, picking the counterpart port with `ports_match`.
+fn try_link_by_channel(
+    parent_name: &str,
+    port: &Port,
+    state_rc: &Rc<RefCell<State>>,
+    state: &mut State,
+    config: &ConfigCache,
+    core: &pw::Core,
+) {
+    let Some((other_name, other_dir)) = config.connect.get(parent_name) else { return };
+    // If this port is the same direction as the port we're trying to link to we have the wrong port
+    if port.direction == *other_dir {
+        return;
+    }
+
+    let Some(other_node) = state.node_by_name.get(other_name) else { return };
+    let Some(other_port) = state.relevant_nodes.get(other_node).unwrap().ports.iter()
+        .find(|p| p.direction != port.direction && ports_match(port, p))
+        .cloned() else { return };
+
+    let this_desc = format!("{} ({})", parent_name, port_label(port));
+    let other_desc = format!("{} ({})", other_name, port_label(&other_port));
+    if port.direction == Direction::IN {
+        establish_link(state_rc, state, core, port, &other_port, &this_desc, &other_desc);
+    } else {
+        establish_link(state_rc, state, core, &other_port, port, &other_desc, &this_desc);
+    }
+}
+
+// Tries to link `port` (which belongs to the node named `parent_name`) to its
+// configured counterpart, if that counterpart's port is already known. Shared
+// by newly-arriving ports and by a config reload re-checking ports that were
+// already there before the new rule showed up. An explicit `node:port` rule
+// for this exact port always wins over the same-channel fallback.
+pub fn try_link_port(
+    parent_name: &str,
+    port: &Port,
+    state_rc: &Rc<RefCell<State>>,
+    state: &mut State,
+    config: &ConfigCache,
+    core: &pw::Core,
+) {
+    let pair = Pair::new(parent_name, port.name.as_str());
+    if config.port_connect.contains_key(&pair) {
+        try_link_explicit_pair(parent_name, port, state_rc, state, config, core);
+    } else {
+        try_link_by_channel(parent_name, port, state_rc, state, config, core);
+    }
+}
+
+pub fn on_new_port(port: Port, state_rc: &Rc<RefCell<State>>, config: &ConfigCache, core: &pw::Core) {
+    let mut state = state_rc.borrow_mut();
+    if let Some(parent) = state.relevant_nodes.get(&port.node) {
+        let parent_name = parent.name.clone();
+        try_link_port(&parent_name, &port, state_rc, &mut state, config, core);
+        state.relevant_nodes.get_mut(&port.node).unwrap().ports.push(port);
+    }
+}
+
+// Re-checks every port already known to belong to `node_id` against the
+// current config, used right after a rule is added by a config reload or a
+// control-socket command.
+pub fn relink_node(node_id: u32, state_rc: &Rc<RefCell<State>>, config: &ConfigCache, core: &pw::Core) {
+    let mut state = state_rc.borrow_mut();
+    let Some(data) = state.relevant_nodes.get(&node_id) else { return };
+    let name = data.name.clone();
+    let ports = data.ports.clone();
+    for port in &ports {
+        try_link_port(&name, port, state_rc, &mut state, config, core);
+    }
+}
+
+pub fn on_new_link(node_in: u32, node_out: u32, id: u32, state: &mut State, config: &ConfigCache, registry: &Registry) {
+    if state.created_links.contains(&id) {
+        return;
+    }
+    if let Some(node_in) = state.relevant_nodes.get(&node_in) {
+        if config.delete_in.contains(&node_in.name) {
+            println!("Deleting input link from {}", node_in.name);
+            registry.destroy_global(id).into_result().unwrap();
+        }
+    }
+    if let Some(node_out) = state.relevant_nodes.get(&node_out) {
+        if config.delete_out.contains(&node_out.name) {
+            println!("Deleting output link from {}", node_out.name);
+            registry.destroy_global(id).into_result().unwrap();
+        }
+    }
+}
+
+fn get_props<'a, const N: usize>(dict: &'a ForeignDict, keys: [&str; N]) -> Option<[&'a str; N]> {
+    let opts = keys.map(|k| dict.get(k));
+    return unwrap_arr(opts);
+}
+
+fn unwrap_arr<const N: usize, T>(arr: [Option<T>; N]) -> Option<[T; N]> {
+    if arr.iter().any(|x| x.is_none()) {
+        return None;
+    }
+    return Some(arr.map(|x| unsafe { x.unwrap_unchecked() }));
+}
+
+enum LoopMessage {
+    ReloadConfig,
+}
+
+// Runs on its own thread purely to turn "got a SIGHUP" into a message the
+// single-threaded PipeWire loop can pick up whenever it's ready.
+fn spawn_sighup_watcher(sender: pw::channel::Sender<LoopMessage>) {
+    std::thread::spawn(move || {
+        let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP])
+            .expect("Failed to register SIGHUP handler");
+        for _ in signals.forever() {
+            if sender.send(LoopMessage::ReloadConfig).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn reload_config(
+    path: &PathBuf,
+    cfg_rc: &Rc<RefCell<ConfigCache>>,
+    state: &Rc<RefCell<State>>,
+    core: &pw::Core,
+) {
+    let file = match config::load_file(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let mut new_config = ConfigCache::default();
+    if let Err(e) = new_config.merge_file(file) {
+        eprintln!("{}", e);
+        return;
+    }
+
+    let added_rules = {
+        let mut cfg = cfg_rc.borrow_mut();
+        let added_rules = cfg.added_connect_rules(&new_config);
+        *cfg = new_config;
+        added_rules
+    };
+
+    for node_name in added_rules {
+        let node_id = state.borrow().node_by_name.get(&node_name).copied();
+        if let Some(node_id) = node_id {
+            relink_node(node_id, state, &cfg_rc.borrow(), core);
+        }
+    }
+    println!("Reloaded config from {}", path.display());
+}
+
+// The parsed shape of a control-socket line, split out from
+// `handle_control_command` so the line-protocol parsing can be unit-tested
+// without a live PipeWire connection.
+#[derive(Debug, PartialEq, Eq)]
+enum ControlCommand<'a> {
+    Connect { output: &'a str, input: &'a str },
+    Disconnect { output: &'a str, input: &'a str },
+    DeleteIn { node: &'a str },
+    List,
+}
+
+// Parses one line read from a control-socket connection. The error string
+// does not include the `"error: "` prefix or trailing newline the caller
+// writes back to the client.
+fn parse_control_command(line: &str) -> Result<ControlCommand<'_>, String> {
+    let mut parts = line.split_whitespace();
+    match parts.next() {
+        Some("connect") => {
+            let (Some(output), Some(input)) = (parts.next(), parts.next()) else {
+                return Err("usage: connect OUT IN".to_string());
+            };
+            Ok(ControlCommand::Connect { output, input })
+        }
+        Some("disconnect") => {
+            let (Some(output), Some(input)) = (parts.next(), parts.next()) else {
+                return Err("usage: disconnect OUT IN".to_string());
+            };
+            Ok(ControlCommand::Disconnect { output, input })
+        }
+        Some("delete-in") => {
+            let Some(node) = parts.next() else {
+                return Err("usage: delete-in NODE".to_string());
+            };
+            Ok(ControlCommand::DeleteIn { node })
+        }
+        Some("list") => Ok(ControlCommand::List),
+        Some(other) => Err(format!("unknown command '{}'", other)),
+        None => Err("empty command".to_string()),
+    }
+}
+
+// Handles one line read from a control-socket connection and returns the
+// text to write back.
+fn handle_control_command(
+    line: &str,
+    cfg_rc: &Rc<RefCell<ConfigCache>>,
+    state_rc: &Rc<RefCell<State>>,
+    registry: &Registry,
+    core: &pw::Core,
+) -> String {
+    let command = match parse_control_command(line) {
+        Ok(command) => command,
+        Err(e) => return format!("error: {}\n", e),
+    };
+    match command {
+        ControlCommand::Connect { output, input } => {
+            if let Err(e) = cfg_rc.borrow_mut().add_connect(output.to_string(), input.to_string()) {
+                return format!("error: {}\n", e);
+            }
+            for name in [output, input] {
+                // `name` may be a `node:port` pair; `node_by_name` is keyed
+                // on the bare node name, so strip the port before looking up.
+                let node_name = name.split_once(':').map_or(name, |(node, _)| node);
+                let node_id = state_rc.borrow().node_by_name.get(node_name).copied();
+                if let Some(node_id) = node_id {
+                    relink_node(node_id, state_rc, &cfg_rc.borrow(), core);
+                }
+            }
+            "ok\n".to_string()
+        }
+        ControlCommand::Disconnect { output, input } => {
+            disconnect_nodes(output, input, cfg_rc, state_rc, registry)
+        }
+        ControlCommand::DeleteIn { node } => {
+            cfg_rc.borrow_mut().add_delete_in(node.to_string());
+            "ok\n".to_string()
+        }
+        ControlCommand::List => list_state(state_rc),
+    }
+}
+
+// Drops the connect rule between `output` and `input` and destroys any
+// links we created for that node pair. For a `node:port` pair rule this is
+// every link we created between the two nodes, not only the named ports'.
+fn disconnect_nodes(
+    output: &str,
+    input: &str,
+    cfg_rc: &Rc<RefCell<ConfigCache>>,
+    state_rc: &Rc<RefCell<State>>,
+    registry: &Registry,
+) -> String {
+    {
+        let mut cfg = cfg_rc.borrow_mut();
+        match (config::is_pair(output), config::is_pair(input)) {
+            (true, true) => cfg.remove_port_connect(&config::to_pair(output), &config::to_pair(input)),
+            (false, false) => cfg.remove_connect(output, input),
+            _ => return format!(
+                "error: disconnect rule must name either two nodes (\"a\" \"b\") or two node:port pairs (\"a:out\" \"b:in\"), got \"{}\" and \"{}\"\n",
+                output, input
+            ),
+        }
+    }
+
+    let mut state = state_rc.borrow_mut();
+    // `node_by_name` is keyed on the bare node name, so strip any `:port`.
+    let out_node = output.split_once(':').map_or(output, |(node, _)| node);
+    let in_node = input.split_once(':').map_or(input, |(node, _)| node);
+    let (Some(&out_id), Some(&in_id)) = (state.node_by_name.get(out_node), state.node_by_name.get(in_node)) else {
+        return "ok (rule removed, no matching nodes present)\n".to_string();
+    };
+    let Some(link_ids) = state.links_by_nodes.remove(&(out_id, in_id)) else {
+        return "ok (rule removed, nothing to disconnect)\n".to_string();
+    };
+    for id in &link_ids {
+        registry.destroy_global(*id).into_result().unwrap();
+        state.created_links.remove(id);
+    }
+    format!("ok ({} link(s) destroyed)\n", link_ids.len())
+}
+
+fn list_state(state_rc: &Rc<RefCell<State>>) -> String {
+    let state = state_rc.borrow();
+    let mut out = String::new();
+    for data in state.relevant_nodes.values() {
+        out.push_str(&format!("node {}\n", data.name));
+        for port in &data.ports {
+            out.push_str(&format!("  port {} id={} direction={:?} channel={:?} media_type={:?}\n", port.name, port.id, port.direction, port.channel, port.media_type));
+        }
+    }
+    out.push_str(&format!("created_links: {:?}\n", state.created_links));
+    out
+}
+
+fn handle_control_connection(
+    stream: UnixStream,
+    cfg_rc: &Rc<RefCell<ConfigCache>>,
+    state_rc: &Rc<RefCell<State>>,
+    registry: &Registry,
+    core: &pw::Core,
+) {
+    let mut writer = match stream.try_clone() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("control socket: failed to clone connection: {}", e);
+            return;
+        }
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { break };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let response = handle_control_command(line, cfg_rc, state_rc, registry, core);
+        if writer.write_all(response.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+fn listener_thread(cfg: ConfigCache, config_path: Option<PathBuf>, control_socket_path: Option<PathBuf>) {
+    let mainloop = pw::MainLoop::new().expect("Failed to create MainLoop for listener thread");
+    let context = pw::Context::new(&mainloop).expect("Failed to create PipeWire Context");
+    let core = Rc::new(context
+        .connect(None)
+        .expect("Failed to connect to PipeWire Core"));
+    let registry = Rc::new(core.get_registry().expect("Failed to get Registry"));
+    let state = Rc::new(RefCell::new(State::default()));
+    let state2 = state.clone();
+
+    let registry2 = registry.clone();
+    let cfg1 = Rc::new(RefCell::new(cfg));
+    let cfg2 = cfg1.clone();
+    let core1 = core.clone();
+    let _listener = registry
+        .add_listener_local()
+        .global(move |global| {
+            if global.props.is_none() { return }
+            let props = global.props.as_ref().unwrap();
+            let id = global.id;
+            match global.type_ {
+                ObjectType::Node => {
+                    if let Some(name) = props.get("node.name") {
+                        on_new_node(name.to_owned(), global.id, state.borrow_mut().deref_mut());
+                    }
+                },
+                ObjectType::Port => {
+                    // `audio.channel` and `format.dsp` are optional: MIDI, video,
+                    // and generic control ports don't carry an audio channel.
+                    if let Some([node_id, name, dir_str]) = get_props(props, ["node.id", "port.name", "port.direction"]) {
+                        if let Some(node) = u32::parse_value(node_id) {
+                            let direction = if dir_str == "in" { Direction::IN } else { Direction::OUT };
+                            let channel = props.get("audio.channel").map(str::to_owned);
+                            let media_type = props.get("format.dsp").map(media_type_of);
+                            let port = Port{id, node, channel, name: name.to_owned(), media_type, direction};
+                            on_new_port(port, &state, &cfg1.borrow(), &core1);
+                        }
+                    }
+                },
+                ObjectType::Link => {
+                    if let Some(vals) = get_props(props, ["link.input.node", "link.output.node"]) {
+                        let ids = vals.map(u32::parse_value);
+                        if let Some([node_in, node_out]) = unwrap_arr(ids) {
+                            on_new_link(node_in, node_out, id, state.borrow_mut().deref_mut(), &cfg1.borrow(), &registry2);
+                        }
+                    }
+                },
+                _ => {}
+            }
+        })
+        .global_remove(move |id| {
+            on_delete(id, state2.borrow_mut().deref_mut());
+        })
+        .register();
+
+    // SIGHUP reload is only meaningful if we loaded rules from a file in the
+    // first place; with pure-CLI rules there's nothing on disk to re-read.
+    let _receiver = config_path.map(|path| {
+        let (sender, receiver) = pw::channel::channel::<LoopMessage>();
+        spawn_sighup_watcher(sender);
+        let cfg3 = cfg2.clone();
+        let state3 = state.clone();
+        let core2 = core.clone();
+        receiver.attach(&mainloop, move |msg| match msg {
+            LoopMessage::ReloadConfig => reload_config(&path, &cfg3, &state3, &core2),
+        })
+    });
+
+    // Kept alive for the lifetime of the loop; dropping it would unregister
+    // the fd source and stop accepting control connections.
+    let _control_io = control_socket_path.map(|path| {
+        if path.exists() {
+            let _ = std::fs::remove_file(&path);
+        }
+        let socket = UnixListener::bind(&path)
+            .unwrap_or_else(|e| panic!("Failed to bind control socket {}: {}", path.display(), e));
+        socket.set_nonblocking(true).expect("Failed to set control socket non-blocking");
+        let fd = socket.as_raw_fd();
+
+        let cfg4 = cfg2.clone();
+        let state4 = state.clone();
+        let registry3 = registry.clone();
+        let core3 = core.clone();
+        // TODO: find the io-source API the same way the examples do; this
+        // attaches the listener's fd to the loop so accepting a connection
+        // doesn't need its own thread.
+        mainloop.loop_().add_io(fd, IoFlags::IN, move |_mask| {
+            loop {
+                match socket.accept() {
+                    Ok((stream, _addr)) => handle_control_connection(stream, &cfg4, &state4, &registry3, &core3),
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        eprintln!("control socket: accept failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        })
+    });
+
+    mainloop.run();
+}
+
+/// Embeds pipewire-autolink's link-creation/deletion logic in another
+/// program. Build one with a [`config::ConfigCache`] describing the desired
+/// rules, optionally point it at a config file (for SIGHUP reload) or a
+/// control socket, then call [`AutoLinker::run`].
+///
+/// Callers that already drive their own PipeWire main loop and don't want a
+/// second one can skip this type entirely and call [`on_new_node`],
+/// [`on_new_port`], and [`on_new_link`] directly against their own [`State`].
+pub struct AutoLinker {
+    config: ConfigCache,
+    config_path: Option<PathBuf>,
+    control_socket_path: Option<PathBuf>,
+}
+
+impl AutoLinker {
+    pub fn new(config: ConfigCache) -> Self {
+        AutoLinker { config, config_path: None, control_socket_path: None }
+    }
+
+    /// Enables SIGHUP-triggered reload from `path`, a TOML file in the same
+    /// shape `config::load_file` accepts.
+    pub fn with_config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Opens a Unix control socket at `path` once [`AutoLinker::run`] starts,
+    /// accepting the line commands documented on the `--control-socket` flag.
+    pub fn with_control_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.control_socket_path = Some(path.into());
+        self
+    }
+
+    /// Connects to PipeWire and runs the autolinker. Blocks the calling
+    /// thread for as long as the main loop runs.
+    pub fn run(self) {
+        listener_thread(self.config, self.config_path, self.control_socket_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn on_new_node_tracks_nodes_with_no_rule_yet() {
+        let mut state = State::default();
+        on_new_node("unreferenced".to_string(), 42, &mut state);
+        assert_eq!(state.node_by_name.get("unreferenced"), Some(&42));
+        assert!(state.relevant_nodes.contains_key(&42));
+    }
+
+    fn port(channel: Option<&str>, name: &str, media_type: Option<&str>) -> Port {
+        Port {
+            id: 0,
+            node: 0,
+            channel: channel.map(str::to_string),
+            name: name.to_string(),
+            media_type: media_type.map(str::to_string),
+            direction: Direction::OUT,
+        }
+    }
+
+    #[test]
+    fn ports_match_by_channel_when_both_have_one() {
+        let a = port(Some("FL"), "playback_FL", None);
+        let b = port(Some("FL"), "capture_FL", None);
+        let c = port(Some("FR"), "capture_FR", None);
+        assert!(ports_match(&a, &b));
+        assert!(!ports_match(&a, &c));
+    }
+
+    #[test]
+    fn ports_match_by_name_when_channel_is_missing() {
+        let a = port(None, "midi_in", None);
+        let b = port(None, "midi_in", None);
+        let c = port(None, "midi_out", None);
+        assert!(ports_match(&a, &b));
+        assert!(!ports_match(&a, &c));
+    }
+
+    #[test]
+    fn ports_match_rejects_differing_media_types() {
+        let a = port(Some("FL"), "a", Some("audio"));
+        let b = port(Some("FL"), "b", Some("midi"));
+        assert!(!ports_match(&a, &b));
+    }
+
+    #[test]
+    fn media_type_of_takes_the_last_word() {
+        assert_eq!(media_type_of("32 bit float mono audio"), "audio");
+        assert_eq!(media_type_of("8 bit raw midi"), "midi");
+        assert_eq!(media_type_of("audio"), "audio");
+    }
+
+    #[test]
+    fn parse_control_command_connect() {
+        assert_eq!(
+            parse_control_command("connect a b").unwrap(),
+            ControlCommand::Connect { output: "a", input: "b" }
+        );
+    }
+
+    #[test]
+    fn parse_control_command_connect_missing_args() {
+        assert!(parse_control_command("connect a").is_err());
+    }
+
+    #[test]
+    fn parse_control_command_disconnect() {
+        assert_eq!(
+            parse_control_command("disconnect a b").unwrap(),
+            ControlCommand::Disconnect { output: "a", input: "b" }
+        );
+    }
+
+    #[test]
+    fn parse_control_command_delete_in() {
+        assert_eq!(
+            parse_control_command("delete-in a").unwrap(),
+            ControlCommand::DeleteIn { node: "a" }
+        );
+    }
+
+    #[test]
+    fn parse_control_command_delete_in_missing_arg() {
+        assert!(parse_control_command("delete-in").is_err());
+    }
+
+    #[test]
+    fn parse_control_command_list() {
+        assert_eq!(parse_control_command("list").unwrap(), ControlCommand::List);
+    }
+
+    #[test]
+    fn parse_control_command_unknown() {
+        assert_eq!(parse_control_command("frobnicate").unwrap_err(), "unknown command 'frobnicate'");
+    }
+
+    #[test]
+    fn parse_control_command_empty() {
+        assert_eq!(parse_control_command("").unwrap_err(), "empty command");
+    }
+}